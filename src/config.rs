@@ -0,0 +1,56 @@
+use std::{fs, path::Path};
+
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+
+/// A panel definition loaded from a config file: the display dimensions and the
+/// list of RGB palette entries the image is dithered down to.
+#[derive(Debug, Clone, Deserialize)]
+pub struct PanelConfig {
+    pub width: u32,
+    pub height: u32,
+    pub palette: Vec<[u8; 3]>,
+    /// Bits used to pack each pixel's palette index, e.g. 4 for one nibble per
+    /// pixel. This is a property of the panel's wire protocol, not just a
+    /// function of how many colors are listed, so it isn't inferred from
+    /// `palette.len()`. Defaults to the minimum needed to index the palette
+    /// when a config file doesn't specify it.
+    #[serde(default)]
+    pub bits_per_pixel: Option<u32>,
+}
+
+impl PanelConfig {
+    /// The built-in WaveShare 5.65" 7-color configuration used when no config
+    /// file is supplied. The hardware protocol always packs at 4 bits per
+    /// pixel (one nibble, two pixels per byte), with 9 of the 16 nibble values
+    /// unused, regardless of only 7 colors being listed.
+    pub fn waveshare_5in65() -> Self {
+        PanelConfig {
+            width: 600,
+            height: 448,
+            palette: vec![
+                [0, 0, 0],       // Black
+                [255, 255, 255], // White
+                [67, 138, 28],   // Green
+                [100, 64, 255],  // Blue
+                [191, 0, 0],     // Red
+                [255, 243, 56],  // Yellow
+                [232, 126, 0],   // Orange
+            ],
+            bits_per_pixel: Some(4),
+        }
+    }
+
+    /// Loads a panel config from a `.json` or `.toml` file, picked by extension.
+    pub fn load(path: &Path) -> Result<Self> {
+        let contents = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config {:?}", path))?;
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("json") => serde_json::from_str(&contents)
+                .with_context(|| format!("Failed to parse JSON config {:?}", path)),
+            Some("toml") => toml::from_str(&contents)
+                .with_context(|| format!("Failed to parse TOML config {:?}", path)),
+            _ => Err(anyhow!("Config {:?} must be a .json or .toml file", path)),
+        }
+    }
+}
@@ -0,0 +1,93 @@
+use std::path::Path;
+
+use anyhow::{anyhow, Context, Result};
+use image::{io::Reader, RgbImage};
+
+/// File extensions routed through the camera RAW pipeline.
+const RAW_EXTENSIONS: &[&str] = &[
+    "dng", "cr2", "cr3", "nef", "arw", "rw2", "orf", "raf", "srw", "pef", "raw",
+];
+
+/// File extensions routed through the HEIF/HEIC decoder.
+const HEIF_EXTENSIONS: &[&str] = &["heic", "heif", "hif"];
+
+/// Decodes an input file into an 8-bit RGB buffer, dispatching camera RAW and
+/// HEIF/HEIC files to their (feature-gated) pipelines and everything else to
+/// the `image` crate.
+pub fn load_rgb(path: &Path) -> Result<RgbImage> {
+    let ext = path
+        .extension()
+        .and_then(|e| e.to_str())
+        .map(str::to_ascii_lowercase);
+
+    match ext.as_deref() {
+        Some(ext) if RAW_EXTENSIONS.contains(&ext) => decode_raw(path),
+        Some(ext) if HEIF_EXTENSIONS.contains(&ext) => decode_heif(path),
+        _ => Ok(Reader::open(path)
+            .with_context(|| format!("Failed to open image {:?}", path))?
+            .decode()
+            .with_context(|| format!("Failed to decode image {:?}", path))?
+            .to_rgb8()),
+    }
+}
+
+/// Decodes a camera RAW/DNG file through a rawloader + imagepipe pipeline.
+#[cfg(feature = "raw")]
+fn decode_raw(path: &Path) -> Result<RgbImage> {
+    let decoded = imagepipe::simple_decode_8bit(path, 0, 0)
+        .map_err(|e| anyhow!("Failed to decode RAW image {:?}: {}", path, e))?;
+    RgbImage::from_raw(decoded.width as u32, decoded.height as u32, decoded.data)
+        .ok_or_else(|| anyhow!("RAW image {:?} produced an unexpected buffer size", path))
+}
+
+#[cfg(not(feature = "raw"))]
+fn decode_raw(path: &Path) -> Result<RgbImage> {
+    Err(anyhow!(
+        "Skipping RAW image {:?}: rebuild with `--features raw` to enable RAW support",
+        path
+    ))
+}
+
+/// Decodes a HEIF/HEIC file to RGB via a libheif binding.
+#[cfg(feature = "heif")]
+fn decode_heif(path: &Path) -> Result<RgbImage> {
+    use libheif_rs::{ColorSpace, HeifContext, LibHeif, RgbChroma};
+
+    let lib = LibHeif::new();
+    let ctx = HeifContext::read_from_file(
+        path.to_str()
+            .ok_or_else(|| anyhow!("HEIF path {:?} is not valid UTF-8", path))?,
+    )
+    .map_err(|e| anyhow!("Failed to open HEIF image {:?}: {}", path, e))?;
+
+    let handle = ctx
+        .primary_image_handle()
+        .map_err(|e| anyhow!("Failed to read HEIF image {:?}: {}", path, e))?;
+    let image = lib
+        .decode(&handle, ColorSpace::Rgb(RgbChroma::Rgb), None)
+        .map_err(|e| anyhow!("Failed to decode HEIF image {:?}: {}", path, e))?;
+
+    let planes = image.planes();
+    let plane = planes
+        .interleaved
+        .ok_or_else(|| anyhow!("HEIF image {:?} has no interleaved RGB plane", path))?;
+
+    // The decoded plane is row-padded to `stride`; copy out the tight RGB rows.
+    let (width, height, stride) = (plane.width as usize, plane.height as usize, plane.stride);
+    let mut buf = Vec::with_capacity(width * height * 3);
+    for y in 0..height {
+        let start = y * stride;
+        buf.extend_from_slice(&plane.data[start..start + width * 3]);
+    }
+
+    RgbImage::from_raw(plane.width, plane.height, buf)
+        .ok_or_else(|| anyhow!("HEIF image {:?} produced an unexpected buffer size", path))
+}
+
+#[cfg(not(feature = "heif"))]
+fn decode_heif(path: &Path) -> Result<RgbImage> {
+    Err(anyhow!(
+        "Skipping HEIF image {:?}: rebuild with `--features heif` to enable HEIF support",
+        path
+    ))
+}
@@ -1,77 +1,528 @@
-use std::{fs::File, io::Write};
+use std::{
+    fmt::{self, Debug},
+    fs::File,
+    io::Write,
+    path::Path,
+    str::FromStr,
+};
 
 use anyhow::{anyhow, Context, Result};
 use exoquant::{
-    ditherer::{Ditherer, FloydSteinberg},
-    Color, ColorSpace, Remapper, SimpleColorSpace,
+    ditherer::{self, Ditherer, FloydSteinberg},
+    Color, Remapper, SimpleColorSpace,
+};
+use image::{
+    imageops::{self, FilterType},
+    ImageBuffer, Rgb, RgbImage,
 };
-use image::{io::Reader, ImageBuffer, Rgb};
-use lazy_static::lazy_static;
 
+use crate::config::PanelConfig;
+use crate::decode::load_rgb;
 use crate::image_info::ImageInfo;
 
 const fn color(r: u8, g: u8, b: u8) -> Color {
     Color { r, g, b, a: 255 }
 }
 
-const PALETTE: &[Color] = &[
-    color(0, 0, 0),       // Black
-    color(255, 255, 255), // White
-    color(67, 138, 28),   // Green
-    color(100, 64, 255),  // Blue
-    color(191, 0, 0),     // Red
-    color(255, 243, 56),  // Yellow
-    color(232, 126, 0),   // Orange
+// Default is 2.2, but bumping it up slightly to get a bit more contrast.
+const DITHER_GAMMA: f64 = 2.3;
+
+// The classic recursive 4×4 Bayer matrix. Normalized to [0, 1) by dividing by
+// 16 and centered around zero at use by subtracting 0.5.
+const BAYER_4X4: [[u8; 4]; 4] = [
+    [0, 8, 2, 10],
+    [12, 4, 14, 6],
+    [3, 11, 1, 9],
+    [15, 7, 13, 5],
 ];
 
-const WIDTH: u32 = 600;
-const HEIGHT: u32 = 448;
+/// Selectable dithering strategy. `None` and `FloydSteinberg` are handed off
+/// to exoquant, while `Atkinson` and `Bayer` are computed against the palette
+/// directly.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dither {
+    /// Nearest palette color with no error diffusion.
+    None,
+    /// Classic Floyd–Steinberg error diffusion.
+    FloydSteinberg,
+    /// Atkinson error diffusion: only 6/8 of each pixel's error is spread.
+    Atkinson,
+    /// Ordered Bayer dithering against a precomputed threshold matrix.
+    Bayer,
+}
 
-// Default is 2.2, but bumping it up slightly to get a bit more contrast.
-const DITHER_GAMMA: f64 = 2.3;
+impl FromStr for Dither {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "none" => Ok(Dither::None),
+            "floyd-steinberg" => Ok(Dither::FloydSteinberg),
+            "atkinson" => Ok(Dither::Atkinson),
+            "bayer" => Ok(Dither::Bayer),
+            other => Err(anyhow!("Unknown dither mode {:?}", other)),
+        }
+    }
+}
+
+/// How to fit an off-size input onto the panel.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResizeMode {
+    /// Scale to fit entirely inside the panel, letterboxing the remainder.
+    Fit,
+    /// Scale to cover the panel, then center-crop the overflow.
+    Fill,
+    /// Scale each axis independently to exactly fill the panel.
+    Stretch,
+}
+
+impl FromStr for ResizeMode {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "fit" => Ok(ResizeMode::Fit),
+            "fill" | "cover" => Ok(ResizeMode::Fill),
+            "stretch" => Ok(ResizeMode::Stretch),
+            other => Err(anyhow!("Unknown resize mode {:?}", other)),
+        }
+    }
+}
+
+/// A `FilterType` that can be parsed from the command line.
+#[derive(Debug, Clone, Copy)]
+pub struct Filter(pub FilterType);
+
+impl FromStr for Filter {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let filter = match s {
+            "nearest" => FilterType::Nearest,
+            "triangle" => FilterType::Triangle,
+            "catmull-rom" | "catmullrom" => FilterType::CatmullRom,
+            "gaussian" => FilterType::Gaussian,
+            "lanczos3" => FilterType::Lanczos3,
+            other => return Err(anyhow!("Unknown resize filter {:?}", other)),
+        };
+        Ok(Filter(filter))
+    }
+}
 
-lazy_static! {
-    static ref COLOR_SPACE: SimpleColorSpace = SimpleColorSpace {
-        dither_gamma: DITHER_GAMMA,
-        ..Default::default()
+/// Pre-dither tone adjustments applied to the RGB buffer in linear light.
+#[derive(Debug, Clone, Copy)]
+pub struct Tone {
+    /// Additive brightness in linear light (roughly -1.0 to 1.0).
+    pub brightness: f64,
+    /// Contrast multiplier applied around mid-gray (1.0 leaves it unchanged).
+    pub contrast: f64,
+    /// Per-channel (R, G, B) gamma used to convert to/from linear light for
+    /// the brightness and contrast adjustments above.
+    pub gamma: [f64; 3],
+}
+
+/// Settings controlling how off-size inputs are rescaled to the panel.
+#[derive(Debug, Clone, Copy)]
+pub struct Resize {
+    pub mode: ResizeMode,
+    pub filter: FilterType,
+    /// Palette index used as the letterbox background in `Fit` mode.
+    pub background: usize,
+}
+
+/// Maps an 8-bit channel into the gamma-corrected linear space the palette
+/// distances are measured in.
+fn to_linear(c: u8, gamma: f64) -> f64 {
+    (c as f64 / 255.0).powf(gamma)
+}
+
+/// Squared Euclidean distance between two linear-space colors.
+fn dist_sq(a: [f64; 3], b: [f64; 3]) -> f64 {
+    (0..3).map(|c| (a[c] - b[c]).powi(2)).sum()
+}
+
+/// Returns the index of the palette color closest to `pixel` in linear space.
+fn nearest_index(pixel: [f64; 3], palette: &[[f64; 3]]) -> usize {
+    palette
+        .iter()
+        .enumerate()
+        .min_by(|(_, a), (_, b)| dist_sq(pixel, **a).partial_cmp(&dist_sq(pixel, **b)).unwrap())
+        .map(|(i, _)| i)
+        .unwrap()
+}
+
+/// Average distance from each palette color to its nearest neighbor, used to
+/// scale the Bayer threshold so it nudges pixels by roughly one step.
+fn average_spread(palette: &[[f64; 3]]) -> f64 {
+    let nearest: Vec<f64> = palette
+        .iter()
+        .enumerate()
+        .map(|(i, a)| {
+            palette
+                .iter()
+                .enumerate()
+                .filter(|(j, _)| *j != i)
+                .map(|(_, b)| dist_sq(*a, *b).sqrt())
+                .fold(f64::INFINITY, f64::min)
+        })
+        .collect();
+    nearest.iter().sum::<f64>() / nearest.len() as f64
+}
+
+/// Number of bits needed to index `len` palette entries, i.e. ceil(log2(len)).
+/// Used as a fallback when a config doesn't specify `bits_per_pixel`
+/// explicitly; real panel protocols can pack wider than this implies (see
+/// `PanelConfig::waveshare_5in65`).
+fn bits_per_index(len: usize) -> u32 {
+    let len = len.max(2);
+    usize::BITS - (len - 1).leading_zeros()
+}
+
+/// A runtime-configured panel: its geometry, palette (in both 8-bit and linear
+/// forms), and the precomputed state the dithering strategies need.
+pub struct Panel {
+    pub width: u32,
+    pub height: u32,
+    palette: Vec<Color>,
+    palette_linear: Vec<[f64; 3]>,
+    spread: f64,
+    bits_per_pixel: u32,
+    pub(crate) gamma: f64,
+    color_space: SimpleColorSpace,
+    floyd_steinberg: FloydSteinberg,
+    none: ditherer::None,
+}
+
+impl Debug for Panel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Panel")
+            .field("width", &self.width)
+            .field("height", &self.height)
+            .field("palette", &self.palette)
+            .field("bits_per_pixel", &self.bits_per_pixel)
+            .finish()
+    }
+}
+
+impl Panel {
+    /// Builds a panel from a loaded config, precomputing the linear palette,
+    /// the Bayer spread, and the bits-per-pixel used when packing the output.
+    /// `gamma` overrides the default dither gamma when supplied. Errors if the
+    /// config's palette or geometry is too degenerate to dither against (e.g.
+    /// fewer than two colors, or a zero width/height).
+    pub fn new(config: &PanelConfig, gamma: Option<f64>) -> Result<Self> {
+        if config.palette.len() < 2 {
+            return Err(anyhow!(
+                "Panel palette must have at least 2 colors, got {}",
+                config.palette.len()
+            ));
+        }
+        if config.width == 0 || config.height == 0 {
+            return Err(anyhow!(
+                "Panel dimensions must be non-zero, got {}x{}",
+                config.width,
+                config.height
+            ));
+        }
+
+        let gamma = gamma.unwrap_or(DITHER_GAMMA);
+        let palette: Vec<Color> = config
+            .palette
+            .iter()
+            .map(|c| color(c[0], c[1], c[2]))
+            .collect();
+        let palette_linear: Vec<[f64; 3]> = palette
+            .iter()
+            .map(|c| [to_linear(c.r, gamma), to_linear(c.g, gamma), to_linear(c.b, gamma)])
+            .collect();
+
+        Ok(Panel {
+            width: config.width,
+            height: config.height,
+            spread: average_spread(&palette_linear),
+            bits_per_pixel: config
+                .bits_per_pixel
+                .unwrap_or_else(|| bits_per_index(palette.len())),
+            palette_linear,
+            gamma,
+            color_space: SimpleColorSpace {
+                dither_gamma: gamma,
+                ..Default::default()
+            },
+            floyd_steinberg: FloydSteinberg::new(),
+            none: ditherer::None,
+            palette,
+        })
+    }
+
+    /// Builds the index remapper for the chosen strategy. Returned as a trait
+    /// object so the per-image loop stays agnostic to which exoquant ditherer
+    /// (if any) backs it; borrows the panel so the error-diffusion remappers
+    /// can reference its palette and color space.
+    pub fn remapper(&self, dither: Dither) -> Box<dyn Remap + Sync + '_> {
+        match dither {
+            Dither::None => Box::new(ExoRemap(Remapper::new(
+                &self.palette,
+                &self.color_space,
+                &self.none,
+            ))),
+            Dither::FloydSteinberg => Box::new(ExoRemap(Remapper::new(
+                &self.palette,
+                &self.color_space,
+                &self.floyd_steinberg,
+            ))),
+            Dither::Atkinson => Box::new(DirectRemap {
+                panel: self,
+                kind: Direct::Atkinson,
+            }),
+            Dither::Bayer => Box::new(DirectRemap {
+                panel: self,
+                kind: Direct::Bayer,
+            }),
+        }
+    }
+}
+
+/// Turns a slice of pixels into palette indices for the whole image.
+pub trait Remap {
+    fn remap(&self, pixels: &[Color], width: usize) -> Vec<u8>;
+}
+
+/// Adapter over an exoquant `Remapper`.
+struct ExoRemap<'a, D: Ditherer>(Remapper<'a, SimpleColorSpace, D>);
+
+impl<D: Ditherer + Sync> Remap for ExoRemap<'_, D> {
+    fn remap(&self, pixels: &[Color], width: usize) -> Vec<u8> {
+        self.0.remap(pixels, width)
+    }
+}
+
+/// Which hand-rolled, palette-direct strategy a `DirectRemap` runs.
+#[derive(Debug, Clone, Copy)]
+enum Direct {
+    Atkinson,
+    Bayer,
+}
+
+/// Adapter over the non-exoquant strategies, which work against the panel's
+/// linear palette directly.
+struct DirectRemap<'a> {
+    panel: &'a Panel,
+    kind: Direct,
+}
+
+impl Remap for DirectRemap<'_> {
+    fn remap(&self, pixels: &[Color], width: usize) -> Vec<u8> {
+        let height = pixels.len() / width;
+        match self.kind {
+            Direct::Atkinson => atkinson(pixels, width, height, self.panel.gamma, &self.panel.palette_linear),
+            Direct::Bayer => {
+                bayer(pixels, width, self.panel.gamma, self.panel.spread, &self.panel.palette_linear)
+            }
+        }
+    }
+}
+
+/// Atkinson error-diffusion dithering. Each pixel spreads 1/8 of its
+/// quantization error to six neighbors — right, two-right, the three pixels on
+/// the row below, and two rows straight down — deliberately discarding the
+/// remaining 2/8 for higher local contrast on the palette.
+fn atkinson(
+    pixels: &[Color],
+    width: usize,
+    height: usize,
+    gamma: f64,
+    palette: &[[f64; 3]],
+) -> Vec<u8> {
+    const NEIGHBORS: &[(isize, isize)] =
+        &[(1, 0), (2, 0), (-1, 1), (0, 1), (1, 1), (0, 2)];
+
+    let mut buf: Vec<[f64; 3]> = pixels
+        .iter()
+        .map(|c| [to_linear(c.r, gamma), to_linear(c.g, gamma), to_linear(c.b, gamma)])
+        .collect();
+    let mut output = vec![0u8; pixels.len()];
+
+    for y in 0..height {
+        for x in 0..width {
+            let idx = y * width + x;
+            let old = buf[idx];
+            let nearest = nearest_index(old, palette);
+            output[idx] = nearest as u8;
+
+            let target = palette[nearest];
+            let error = [old[0] - target[0], old[1] - target[1], old[2] - target[2]];
+            for &(dx, dy) in NEIGHBORS {
+                let nx = x as isize + dx;
+                let ny = y as isize + dy;
+                if nx < 0 || ny < 0 || nx as usize >= width || ny as usize >= height {
+                    continue;
+                }
+                let nidx = ny as usize * width + nx as usize;
+                for c in 0..3 {
+                    buf[nidx][c] += error[c] / 8.0;
+                }
+            }
+        }
+    }
+
+    output
+}
+
+/// Ordered Bayer dithering. Adds spread-scaled threshold offsets from the 4×4
+/// matrix to each pixel in gamma-corrected linear space before picking the
+/// nearest palette color, giving deterministic, seam-free results on flat
+/// gradients without any error diffusion.
+fn bayer(pixels: &[Color], width: usize, gamma: f64, spread: f64, palette: &[[f64; 3]]) -> Vec<u8> {
+    pixels
+        .iter()
+        .enumerate()
+        .map(|(idx, c)| {
+            let (x, y) = (idx % width, idx / width);
+            let threshold = spread * (BAYER_4X4[y % 4][x % 4] as f64 / 16.0 - 0.5);
+            let pixel = [
+                to_linear(c.r, gamma) + threshold,
+                to_linear(c.g, gamma) + threshold,
+                to_linear(c.b, gamma) + threshold,
+            ];
+            nearest_index(pixel, palette) as u8
+        })
+        .collect()
+}
+
+/// Applies brightness and contrast to the RGB buffer in gamma-corrected linear
+/// light, clamping back to 8-bit before the pixels are remapped. Contrast is
+/// applied around mid-gray, then brightness is added. Each channel uses its
+/// own gamma from `tone.gamma` to convert to and from linear light.
+fn apply_tone(pixels: &[Color], tone: Tone) -> Vec<Color> {
+    let adjust = |v: u8, gamma: f64| {
+        let lin = (v as f64 / 255.0).powf(gamma);
+        let lin = ((lin - 0.5) * tone.contrast + 0.5 + tone.brightness).clamp(0.0, 1.0);
+        (lin.powf(1.0 / gamma) * 255.0).round() as u8
     };
-    static ref DITHERER: FloydSteinberg = FloydSteinberg::new();
-    pub static ref REMAPPER: Remapper<'static, SimpleColorSpace, FloydSteinberg> =
-        Remapper::new(PALETTE, &COLOR_SPACE, &DITHERER);
+    pixels
+        .iter()
+        .map(|c| {
+            color(
+                adjust(c.r, tone.gamma[0]),
+                adjust(c.g, tone.gamma[1]),
+                adjust(c.b, tone.gamma[2]),
+            )
+        })
+        .collect()
+}
+
+/// Packs palette indices into a bitstream, MSB-first, at `bits` bits per pixel.
+/// For the common 4-bits-per-pixel case this is exactly two nibbles per byte.
+fn pack_indices(indices: &[u8], bits: u32) -> Vec<u8> {
+    let mut out = Vec::with_capacity((indices.len() * bits as usize + 7) / 8);
+    let mut acc: u32 = 0;
+    let mut filled = 0u32;
+    for &index in indices {
+        acc = (acc << bits) | u32::from(index);
+        filled += bits;
+        while filled >= 8 {
+            filled -= 8;
+            out.push((acc >> filled) as u8);
+        }
+    }
+    if filled > 0 {
+        out.push((acc << (8 - filled)) as u8);
+    }
+    out
+}
+
+/// Rescales and crops an arbitrary image to exactly the panel dimensions.
+fn resize_to_panel(img: &RgbImage, panel: &Panel, resize: &Resize) -> Result<RgbImage> {
+    let (w, h) = (panel.width, panel.height);
+    Ok(match resize.mode {
+        ResizeMode::Stretch => imageops::resize(img, w, h, resize.filter),
+        ResizeMode::Fill => {
+            let scale = (w as f64 / img.width() as f64).max(h as f64 / img.height() as f64);
+            let nw = ((img.width() as f64 * scale).round() as u32).max(w);
+            let nh = ((img.height() as f64 * scale).round() as u32).max(h);
+            let scaled = imageops::resize(img, nw, nh, resize.filter);
+            imageops::crop_imm(&scaled, (nw - w) / 2, (nh - h) / 2, w, h).to_image()
+        }
+        ResizeMode::Fit => {
+            let scale = (w as f64 / img.width() as f64).min(h as f64 / img.height() as f64);
+            let nw = ((img.width() as f64 * scale).round() as u32).min(w);
+            let nh = ((img.height() as f64 * scale).round() as u32).min(h);
+            let scaled = imageops::resize(img, nw, nh, resize.filter);
+            let bg = panel.palette.get(resize.background).copied().ok_or_else(|| {
+                anyhow!(
+                    "Background palette index {} is out of range for a {}-color palette",
+                    resize.background,
+                    panel.palette.len()
+                )
+            })?;
+            let mut canvas = ImageBuffer::from_pixel(w, h, Rgb([bg.r, bg.g, bg.b]));
+            imageops::overlay(&mut canvas, &scaled, (w - nw) / 2, (h - nh) / 2);
+            canvas
+        }
+    })
 }
 
 /// Given an image mapping, dithers the image and saves it to the output location.
 /// Optionally saves a PNG preview alongside it.
-pub fn dither_image<C: ColorSpace, D: Ditherer>(
+pub fn dither_image(
     info: &ImageInfo,
-    remapper: &Remapper<C, D>,
+    panel: &Panel,
+    remapper: &(dyn Remap + Sync),
+    resize: Option<Resize>,
+    tone: Option<Tone>,
+    png: bool,
+) -> Result<()> {
+    let img = load_rgb(&info.input)?;
+    dither_rgb(img, &info.input, &info.output, panel, remapper, resize, tone, png)
+}
+
+/// Dithers an already-decoded RGB buffer to `output`, applying the resize and
+/// tone steps first. `source` is only used to label skipped-frame errors, so
+/// the same pipeline can serve both on-disk images and decoded video frames.
+pub(crate) fn dither_rgb(
+    img: RgbImage,
+    source: &Path,
+    output: &Path,
+    panel: &Panel,
+    remapper: &(dyn Remap + Sync),
+    resize: Option<Resize>,
+    tone: Option<Tone>,
     png: bool,
 ) -> Result<()> {
-    let img = Reader::open(&info.input)
-        .with_context(|| format!("Failed to open image {:?}", info.input))?
-        .decode()
-        .with_context(|| format!("Failed to decode image {:?}", info.input))?
-        .to_rgb8();
+    // Bring off-size inputs onto the panel if a resize mode was requested,
+    // otherwise skip them as before.
+    let img = if img.width() != panel.width || img.height() != panel.height {
+        match resize {
+            Some(resize) => resize_to_panel(&img, panel, &resize)?,
+            None => {
+                return Err(anyhow!(
+                    "Skipping {:?} with dimensions {}x{}",
+                    source,
+                    img.width(),
+                    img.height()
+                ))
+            }
+        }
+    } else {
+        img
+    };
 
     let width = img.width();
     let height = img.height();
-    if img.width() != WIDTH || height != HEIGHT {
-        return Err(anyhow!(
-            "Skipping {:?} with dimensions {}x{}",
-            info.input,
-            width,
-            height
-        ));
-    }
 
     let pixels: Vec<_> = img.pixels().map(|p| color(p[0], p[1], p[2])).collect();
+    let pixels = match tone {
+        Some(tone) => apply_tone(&pixels, tone),
+        None => pixels,
+    };
     let dithered = remapper.remap(&pixels, width as usize);
-    let bytes: Vec<_> = dithered.chunks(2).map(|x| x[0] << 4 | x[1]).collect();
+    let bytes = pack_indices(&dithered, panel.bits_per_pixel);
 
-    let mut file = File::create(&info.output)
-        .with_context(|| format!("Failed to create output file {:?}", info.output,))?;
+    let mut file = File::create(output)
+        .with_context(|| format!("Failed to create output file {:?}", output))?;
     file.write_all(&bytes)
-        .with_context(|| format!("Failed to write output file {:?}", info.output,))?;
+        .with_context(|| format!("Failed to write output file {:?}", output))?;
 
     // If requested, map the dithered index values back to the palette colors and save a PNG
     // for preview purposes without having to load the output files on the frame.
@@ -79,12 +530,12 @@ pub fn dither_image<C: ColorSpace, D: Ditherer>(
         let rgb: Vec<_> = dithered
             .iter()
             .flat_map(|i| {
-                let color = PALETTE[*i as usize];
+                let color = panel.palette[*i as usize];
                 [color.r, color.g, color.b]
             })
             .collect();
         let rgb_img = ImageBuffer::<Rgb<u8>, Vec<_>>::from_vec(width, height, rgb).unwrap();
-        let png_file = info.output.with_extension("png");
+        let png_file = output.with_extension("png");
         rgb_img
             .save(&png_file)
             .with_context(|| format!("Failed to write PNG file {:?}", png_file))?;
@@ -65,44 +65,53 @@ pub fn get_images<'a>(
         source_files.shuffle(&mut rng);
     }
 
+    let dest_files = existing_outputs(destination)?;
+
+    // Files in the destination directory are expected to be in the form NNNN-file.bin,
+    // where N is a decimal digit. Start assigning indices at the next free number so we
+    // can add new files without affecting the existing ordering, as well as making
+    // randomization easy.
+    let mut indices = next_index(&dest_files)..;
+
+    // Map each source file to an output file, overwriting an existing one with the same name if present,
+    // otherwise using the next free index at the end.
+    Ok(source_files
+        .into_iter()
+        .map(|file| look_up_info(file, Path::new(&destination), &dest_files, &mut indices))
+        .log_errors_and_collect())
+}
+
+/// Lists the filenames currently in the destination directory, skipping any
+/// that can't be represented as `String`s to keep things simple.
+pub(crate) fn existing_outputs(destination: &Path) -> Result<Vec<String>> {
     let dest_iter = read_dir(destination)
         .with_context(|| format!("Failed to open destination directory {:?}", destination))?;
 
-    // Find all files in the destination directory, ignoring those that can't be represented as `String`s
-    // in order to keep things simple and make it easy to work with them.
-    let dest_files = dest_iter
+    Ok(dest_iter
         .map(|r| {
             r.context("Failed to get file info")?
                 .file_name()
                 .into_string()
                 .map_err(|e| anyhow!("Unsupported filename {:?}", e))
         })
-        .log_errors_and_collect();
+        .log_errors_and_collect())
+}
 
-    // Files in the destination directory are expected to be in the form NNNN-file.bin,
-    // where N is a decimal digit. Parse those and find the largest index of any existing file,
-    // then create an infinite iterator starting at the next number that we can use to assign
-    // indices to the new files. This gives us an easy way to add new files without affecting
-    // the existing ordering, as well as making randomization easy.
-    let last_index = dest_files
+/// Returns the next free NNNN index given the existing destination filenames,
+/// one past the largest index currently present.
+pub(crate) fn next_index(dest_files: &[String]) -> u32 {
+    dest_files
         .par_iter()
         .filter_map(|s| atoi::<u32>(s.as_bytes()))
         .max()
-        .unwrap_or(0);
-    let mut indices = last_index + 1..;
-
-    // Map each source file to an output file, overwriting an existing one with the same name if present,
-    // otherwise using the next free index at the end.
-    Ok(source_files
-        .into_iter()
-        .map(|file| look_up_info(file, Path::new(&destination), &dest_files, &mut indices))
-        .log_errors_and_collect())
+        .unwrap_or(0)
+        + 1
 }
 
 /// Looks up the destination mapping for a source image.
 /// if it already exists in the output directory, that filename will be returned.
 /// Otherwise, it will be assigned the next available index.
-fn look_up_info<'a>(
+pub(crate) fn look_up_info<'a>(
     input: &'a Path,
     destination: &Path,
     destination_files: &[String],
@@ -14,14 +14,20 @@ use indicatif::ParallelProgressIterator;
 use rayon::prelude::*;
 use structopt::StructOpt;
 
-use crate::dither::{dither_image, REMAPPER};
+use crate::config::PanelConfig;
+use crate::dither::{dither_image, Dither, Filter, Panel, Resize, ResizeMode, Tone};
 use crate::image_info::get_images;
+use crate::video::Sampling;
 
+mod config;
+mod decode;
 mod dither;
 mod image_info;
+mod video;
 
-/// Tool to convert images for display on a WaveShare 5.65" 7-color E-Paper display.
-/// Input images should be 600 x 448 pixels.
+/// Tool to convert images for display on e-paper displays. Defaults to the
+/// WaveShare 5.65" 7-color panel (600 x 448); pass `--config` to target a
+/// different palette and geometry, and `--resize` to rescale off-size inputs.
 #[derive(StructOpt, Debug)]
 pub struct Args {
     /// Input image files to be converted
@@ -39,16 +45,114 @@ pub struct Args {
     /// Randomize order of images that don't already exist in the output directory
     #[structopt(short, long)]
     random: bool,
+
+    /// Dithering algorithm: none, floyd-steinberg, atkinson, or bayer
+    #[structopt(short, long, default_value = "floyd-steinberg")]
+    dither: Dither,
+
+    /// Resize and crop off-size inputs to the panel: fit, fill/cover, or stretch
+    #[structopt(long)]
+    resize: Option<ResizeMode>,
+
+    /// Resampling filter: nearest, triangle, catmull-rom, gaussian, or lanczos3
+    #[structopt(long, default_value = "lanczos3")]
+    filter: Filter,
+
+    /// Palette index used to letterbox in `--resize fit`
+    #[structopt(long, default_value = "1")]
+    background: usize,
+
+    /// Panel config (.toml or .json) overriding the default palette and geometry
+    #[structopt(short, long, parse(from_os_str))]
+    config: Option<PathBuf>,
+
+    /// Brightness adjustment in linear light, roughly -1.0 to 1.0
+    #[structopt(long)]
+    brightness: Option<f64>,
+
+    /// Contrast multiplier around mid-gray (1.0 leaves it unchanged)
+    #[structopt(long)]
+    contrast: Option<f64>,
+
+    /// Override the dither gamma (default 2.3)
+    #[structopt(short, long)]
+    gamma: Option<f64>,
+
+    /// Per-channel gamma override for brightness/contrast tone adjustments
+    /// (red); falls back to `--gamma` when unset
+    #[structopt(long)]
+    gamma_r: Option<f64>,
+
+    /// Per-channel gamma override for brightness/contrast tone adjustments
+    /// (green); falls back to `--gamma` when unset
+    #[structopt(long)]
+    gamma_g: Option<f64>,
+
+    /// Per-channel gamma override for brightness/contrast tone adjustments
+    /// (blue); falls back to `--gamma` when unset
+    #[structopt(long)]
+    gamma_b: Option<f64>,
+
+    /// For video/GIF sources, keep every Nth frame
+    #[structopt(long, default_value = "1")]
+    frame_stride: usize,
+
+    /// For video/GIF sources, sample at approximately this many frames per second
+    #[structopt(long)]
+    fps: Option<f64>,
 }
 
 fn main() -> Result<()> {
     let args = Args::from_iter(wild::args());
 
-    let images = get_images(&args.sources, &args.output, args.random)?;
+    let resize = args.resize.map(|mode| Resize {
+        mode,
+        filter: args.filter.0,
+        background: args.background,
+    });
+
+    let config = match &args.config {
+        Some(path) => PanelConfig::load(path)?,
+        None => PanelConfig::waveshare_5in65(),
+    };
+    let panel = Panel::new(&config, args.gamma)?;
+    let remapper = panel.remapper(args.dither);
+
+    let tone = if args.brightness.is_some()
+        || args.contrast.is_some()
+        || args.gamma_r.is_some()
+        || args.gamma_g.is_some()
+        || args.gamma_b.is_some()
+    {
+        Some(Tone {
+            brightness: args.brightness.unwrap_or(0.0),
+            contrast: args.contrast.unwrap_or(1.0),
+            gamma: [
+                args.gamma_r.unwrap_or(panel.gamma),
+                args.gamma_g.unwrap_or(panel.gamma),
+                args.gamma_b.unwrap_or(panel.gamma),
+            ],
+        })
+    } else {
+        None
+    };
+
+    let sampling = match args.fps {
+        Some(fps) => Sampling::Fps(fps),
+        None => Sampling::Stride(args.frame_stride),
+    };
+
+    // Still images run through the parallel pipeline; video/GIF sources are
+    // exploded into sequences of frames afterward so their indices continue on
+    // from whatever the image pass wrote.
+    let (videos, image_sources): (Vec<_>, Vec<_>) =
+        args.sources.iter().cloned().partition(|p| video::is_video(p));
+
+    let images = get_images(&image_sources, &args.output, args.random)?;
     let errors: Vec<_> = images
         .par_iter()
         .progress_count(images.len() as u64)
-        .map(|info| dither_image(info, &REMAPPER, args.png))
+        .map(|info| dither_image(info, &panel, remapper.as_ref(), resize, tone, args.png))
         .filter_map(Result::err)
         .collect();
 
@@ -56,5 +160,20 @@ fn main() -> Result<()> {
         eprintln!("Warning: {}", error);
     }
 
+    for source in &videos {
+        if let Err(error) = video::explode(
+            source,
+            &args.output,
+            sampling,
+            &panel,
+            remapper.as_ref(),
+            resize,
+            tone,
+            args.png,
+        ) {
+            eprintln!("Warning: {}", error);
+        }
+    }
+
     Ok(())
 }
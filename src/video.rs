@@ -0,0 +1,212 @@
+use std::fs::File;
+use std::io::BufReader;
+use std::path::Path;
+
+use anyhow::{anyhow, Result};
+use image::codecs::gif::GifDecoder;
+use image::AnimationDecoder;
+
+use crate::dither::{Panel, Remap, Resize, Tone};
+
+#[cfg(feature = "video")]
+use std::path::PathBuf;
+#[cfg(feature = "video")]
+use crate::dither::dither_rgb;
+#[cfg(feature = "video")]
+use crate::image_info::{existing_outputs, look_up_info, next_index};
+
+/// File extensions always treated as video sources. GIFs are handled
+/// separately in `is_video`, since unlike these a `.gif` file is just as
+/// often a single still image.
+const VIDEO_EXTENSIONS: &[&str] = &["mp4", "mov", "mkv", "avi", "webm", "m4v"];
+
+/// How densely to sample frames out of a video source.
+#[derive(Debug, Clone, Copy)]
+pub enum Sampling {
+    /// Keep every Nth decoded frame.
+    Stride(usize),
+    /// Sample at approximately this many frames per second.
+    Fps(f64),
+}
+
+#[cfg(feature = "video")]
+impl Sampling {
+    /// Number of decoded frames between kept frames, given the source's frame
+    /// rate.
+    fn keep_every(self, src_fps: f64) -> usize {
+        match self {
+            Sampling::Stride(n) => n.max(1),
+            Sampling::Fps(target) if target > 0.0 && src_fps > 0.0 => {
+                (src_fps / target).round().max(1.0) as usize
+            }
+            Sampling::Fps(_) => 1,
+        }
+    }
+}
+
+/// Whether a source path should be handled as a video/GIF rather than a
+/// still. Non-GIF video extensions are always routed to the video pipeline;
+/// a `.gif` is only routed there if it actually has more than one frame, so
+/// an ordinary static GIF still goes through the still-image path.
+pub fn is_video(path: &Path) -> bool {
+    match path.extension().and_then(|e| e.to_str()) {
+        Some(ext) if ext.eq_ignore_ascii_case("gif") => is_animated_gif(path),
+        Some(ext) => VIDEO_EXTENSIONS.contains(&ext.to_ascii_lowercase().as_str()),
+        None => false,
+    }
+}
+
+/// Peeks at a GIF's frames to tell whether it's animated, without decoding
+/// the whole thing. Treats an unreadable file as not animated and leaves the
+/// error for the still-image path to report.
+fn is_animated_gif(path: &Path) -> bool {
+    let open = || -> Result<bool> {
+        let file = File::open(path)?;
+        let decoder = GifDecoder::new(BufReader::new(file))?;
+        Ok(decoder.into_frames().take(2).count() > 1)
+    };
+    open().unwrap_or(false)
+}
+
+/// Explodes a video/GIF into a sequence of dithered `NNNN-name.bin` frames in
+/// the destination directory, sampled according to `sampling`. Frames reuse
+/// the same numbering scheme as still images so they land in playback order.
+#[cfg(feature = "video")]
+pub fn explode(
+    source: &Path,
+    destination: &Path,
+    sampling: Sampling,
+    panel: &Panel,
+    remapper: &(dyn Remap + Sync),
+    resize: Option<Resize>,
+    tone: Option<Tone>,
+    png: bool,
+) -> Result<()> {
+    use ffmpeg_next as ffmpeg;
+    use ffmpeg::format::{input, Pixel};
+    use ffmpeg::media::Type;
+    use ffmpeg::software::scaling::{context::Context as Scaler, flag::Flags};
+    use ffmpeg::util::frame::video::Video as VideoFrame;
+
+    ffmpeg::init().map_err(|e| anyhow!("Failed to initialize ffmpeg: {}", e))?;
+
+    let mut ictx =
+        input(&source).map_err(|e| anyhow!("Failed to open {:?}: {}", source, e))?;
+    let stream = ictx
+        .streams()
+        .best(Type::Video)
+        .ok_or_else(|| anyhow!("No video stream in {:?}", source))?;
+    let stream_index = stream.index();
+
+    let src_fps = {
+        let rate = stream.avg_frame_rate();
+        if rate.denominator() != 0 {
+            f64::from(rate.numerator()) / f64::from(rate.denominator())
+        } else {
+            0.0
+        }
+    };
+    let keep_every = sampling.keep_every(src_fps);
+
+    let decoder_ctx = ffmpeg::codec::context::Context::from_parameters(stream.parameters())
+        .map_err(|e| anyhow!("Failed to build decoder for {:?}: {}", source, e))?;
+    let mut decoder = decoder_ctx
+        .decoder()
+        .video()
+        .map_err(|e| anyhow!("Failed to open video decoder for {:?}: {}", source, e))?;
+
+    let mut scaler = Scaler::get(
+        decoder.format(),
+        decoder.width(),
+        decoder.height(),
+        Pixel::RGB24,
+        decoder.width(),
+        decoder.height(),
+        Flags::BILINEAR,
+    )
+    .map_err(|e| anyhow!("Failed to create RGB scaler for {:?}: {}", source, e))?;
+
+    let dest_files = existing_outputs(destination)?;
+    let mut indices = next_index(&dest_files)..;
+    let stem = source
+        .file_stem()
+        .and_then(|s| s.to_str())
+        .ok_or_else(|| anyhow!("Failed to get filename for {:?}", source))?;
+
+    let mut decoded = 0usize;
+    let mut kept = 0usize;
+
+    let mut receive_and_process =
+        |decoder: &mut ffmpeg::decoder::Video| -> Result<()> {
+            let mut frame = VideoFrame::empty();
+            while decoder.receive_frame(&mut frame).is_ok() {
+                if decoded % keep_every == 0 {
+                    let mut rgb = VideoFrame::empty();
+                    scaler
+                        .run(&frame, &mut rgb)
+                        .map_err(|e| anyhow!("Failed to scale frame: {}", e))?;
+                    let img = frame_to_image(&rgb)?;
+
+                    // Name each frame after the source and its kept-frame number, then
+                    // run it through the standard numbering so it gets the next index.
+                    let name = PathBuf::from(format!("{}-{:04}", stem, kept));
+                    let info = look_up_info(&name, destination, &dest_files, &mut indices)?;
+                    dither_rgb(img, source, &info.output, panel, remapper, resize, tone, png)?;
+                    kept += 1;
+                }
+                decoded += 1;
+            }
+            Ok(())
+        };
+
+    for (stream, packet) in ictx.packets() {
+        if stream.index() == stream_index {
+            decoder
+                .send_packet(&packet)
+                .map_err(|e| anyhow!("Failed to decode {:?}: {}", source, e))?;
+            receive_and_process(&mut decoder)?;
+        }
+    }
+    decoder
+        .send_eof()
+        .map_err(|e| anyhow!("Failed to flush decoder for {:?}: {}", source, e))?;
+    receive_and_process(&mut decoder)?;
+
+    Ok(())
+}
+
+#[cfg(not(feature = "video"))]
+pub fn explode(
+    source: &Path,
+    _destination: &Path,
+    _sampling: Sampling,
+    _panel: &Panel,
+    _remapper: &(dyn Remap + Sync),
+    _resize: Option<Resize>,
+    _tone: Option<Tone>,
+    _png: bool,
+) -> Result<()> {
+    Err(anyhow!(
+        "Skipping video {:?}: rebuild with `--features video` to enable video/GIF support",
+        source
+    ))
+}
+
+/// Copies an RGB24 ffmpeg frame into an `RgbImage`, dropping the row padding.
+#[cfg(feature = "video")]
+fn frame_to_image(
+    frame: &ffmpeg_next::util::frame::video::Video,
+) -> Result<image::RgbImage> {
+    let (width, height) = (frame.width(), frame.height());
+    let stride = frame.stride(0);
+    let data = frame.data(0);
+
+    let mut buf = Vec::with_capacity(width as usize * height as usize * 3);
+    for y in 0..height as usize {
+        let start = y * stride;
+        buf.extend_from_slice(&data[start..start + width as usize * 3]);
+    }
+
+    image::RgbImage::from_raw(width, height, buf)
+        .ok_or_else(|| anyhow!("Decoded frame produced an unexpected buffer size"))
+}